@@ -0,0 +1,78 @@
+use crate::debugger::Debugger;
+use crate::serial::Transport;
+
+/// Everything the CPU needs from system memory. The CPU holds a
+/// `Box<dyn Bus>` rather than a concrete `Memory`, so a `SET n,(HL)` or
+/// any other opcode that reads/writes through the bus can be routed to
+/// whatever implementation is plugged in instead of a fixed array.
+pub trait Bus {
+    fn read8(&mut self, addr: u16) -> u8;
+    fn write8(&mut self, addr: u16, val: u8);
+    fn read16(&mut self, addr: u16) -> u16;
+    fn write16(&mut self, addr: u16, val: u16);
+
+    /// Read without side effects, for the disassembler.
+    fn peek8(&self, addr: u16) -> u8;
+    fn peek16(&self, addr: u16) -> u16;
+
+    /// Advance memory-mapped peripherals (timer, serial, ...) by
+    /// `ticks` M-cycles.
+    fn cycle(&mut self, ticks: u32);
+
+    fn pending_interrupts(&self) -> u8;
+    fn clear_interrupt_flag(&mut self, bit: u8);
+
+    fn set_serial_transport(&mut self, transport: Box<dyn Transport>);
+    fn set_cartridge(&mut self, cartridge: Box<dyn crate::cartridge::Cartridge>);
+
+    /// Map a 256-byte DMG boot ROM in over the cartridge at 0x0000-0x00FF
+    /// until software disables it via a 0xFF50 write.
+    fn set_boot_rom(&mut self, rom: [u8; 0x100]);
+
+    /// Turn hardware-faithful VRAM/OAM access gating on or off.
+    fn set_strict_timing(&mut self, enabled: bool);
+
+    /// Pick which built-in `Color -> RGBA` table `render_rgba` uses.
+    fn set_color_theme(&mut self, theme: crate::ppu::ColorTheme);
+
+    /// Render the current frame as RGBA for a window backend to blit
+    /// directly.
+    fn render_rgba(&self, out: &mut [u8; crate::ppu::FRAME_SIZE]);
+
+    /// Advance an in-flight OAM DMA transfer by one M-cycle.
+    fn dma_tick(&mut self);
+    /// Whether an OAM DMA transfer is currently copying bytes.
+    fn is_dma_active(&self) -> bool;
+
+    /// Whether the mapped cartridge has battery-backed RAM that should
+    /// be persisted to a `.sav` sidecar file.
+    fn has_battery(&self) -> bool;
+    /// Dump the cartridge's external RAM (and any other battery-backed
+    /// state) for a `.sav` sidecar file.
+    fn dump_ram(&self) -> &[u8];
+    /// Restore a buffer produced by `dump_ram`.
+    fn load_ram(&mut self, data: &[u8]);
+
+    /// Mix the APU's four channels down to a stereo sample pair, for a
+    /// host to pull audio from.
+    fn sample(&self) -> (f32, f32);
+
+    /// Snapshot/restore the full memory-mapped state (RAM, peripherals,
+    /// cartridge); see `crate::savestate`.
+    fn write_state(&self, out: &mut Vec<u8>);
+    fn read_state(&mut self, input: &mut crate::savestate::Cursor) -> Result<(), String>;
+
+    fn set_debugger(&mut self, debugger: Option<Debugger>);
+    fn debugger(&self) -> Option<&Debugger>;
+    fn debugger_mut(&mut self) -> Option<&mut Debugger>;
+    fn take_break_hit(&mut self) -> bool;
+}
+
+/// A memory-mapped device that reacts to being read or written, for
+/// buses that dispatch by registered address range instead of a single
+/// hand-written match (e.g. a future cartridge mapper that needs to see
+/// every access, not just ones the CPU's opcode table already special-cases).
+pub trait Peripheral {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+}
@@ -1,19 +1,140 @@
+use std::fs;
+
+use crate::cartridge;
 use crate::cpu::Cpu;
+use crate::savestate::{self, Cursor};
 
 pub struct Console {
     cpu: Cpu,
+    /// Path of the `.sav` sidecar for the loaded ROM, if its cartridge
+    /// has battery-backed RAM; see `load_rom_file`/`save_ram_file`.
+    save_path: Option<String>,
 }
 
 impl Console {
     pub fn new() -> Self {
         let console = Console {
             cpu: Default::default(),
+            save_path: None,
         };
 
         console
     }
 
+    /// Build a console that boots through the real DMG boot sequence: the
+    /// boot ROM overlays the cartridge at 0x0000-0x00FF until it disables
+    /// itself with a 0xFF50 write, and the boot code is responsible for
+    /// bringing the machine up. Registers start zeroed, as they do for
+    /// `new`, which does not load the post-boot register values either.
+    pub fn with_boot_rom(rom: [u8; 0x100]) -> Self {
+        let mut console = Self::new();
+        console.cpu.set_boot_rom(rom);
+        console
+    }
+
+    /// Turn hardware-faithful VRAM/OAM access gating on or off; see
+    /// `Ppu::set_strict_timing`.
+    pub fn set_strict_timing(&mut self, enabled: bool) {
+        self.cpu.set_strict_timing(enabled);
+    }
+
+    /// Pick which built-in `Color -> RGBA` table `render_rgba` uses.
+    pub fn set_color_theme(&mut self, theme: crate::ppu::ColorTheme) {
+        self.cpu.set_color_theme(theme);
+    }
+
+    /// Render the current frame as RGBA for a window backend to blit
+    /// directly.
+    pub fn render_rgba(&self, out: &mut [u8; crate::ppu::FRAME_SIZE]) {
+        self.cpu.render_rgba(out);
+    }
+
+    /// Load a ROM image, validate its header, and map the matching
+    /// cartridge in, replacing the empty no-op cartridge `new` starts
+    /// with.
+    pub fn load_rom(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        let cartridge = cartridge::load(bytes)?;
+        self.cpu.set_cartridge(cartridge);
+        Ok(())
+    }
+
+    /// Read a ROM file from disk and load it; see `load_rom`. If the
+    /// cartridge has battery-backed RAM, also load its `.sav` sidecar
+    /// (the ROM path with its extension swapped for `.sav`), so play
+    /// continues from where it left off.
+    pub fn load_rom_file(&mut self, path: &str) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        self.load_rom(bytes)?;
+
+        if self.cpu.has_battery() {
+            let save_path = Self::sav_path(path);
+            if let Ok(data) = fs::read(&save_path) {
+                self.cpu.load_ram(&data);
+            }
+            self.save_path = Some(save_path);
+        } else {
+            self.save_path = None;
+        }
+        Ok(())
+    }
+
+    /// Write the cartridge's battery-backed RAM out to its `.sav`
+    /// sidecar, if it has one. Call before shutting down.
+    pub fn save_ram_file(&self) -> Result<(), String> {
+        if let Some(path) = &self.save_path {
+            fs::write(path, self.cpu.dump_ram())
+                .map_err(|e| format!("failed to write {}: {}", path, e))?;
+        }
+        Ok(())
+    }
+
+    fn sav_path(rom_path: &str) -> String {
+        match rom_path.rfind('.') {
+            Some(i) => format!("{}.sav", &rom_path[..i]),
+            None => format!("{}.sav", rom_path),
+        }
+    }
+
+    /// Run one opcode to completion, then drain the OAM DMA transfer one
+    /// byte per M-cycle it consumed.
     pub fn cycle(&mut self) {
-        self.cpu.cycle();
+        let m_cycles = self.cpu.step();
+        for _ in 0..m_cycles {
+            self.cpu.dma_tick();
+        }
+    }
+
+    /// Mix the APU's four channels down to a stereo sample pair, for a
+    /// host to pull audio from.
+    pub fn sample(&self) -> (f32, f32) {
+        self.cpu.sample()
+    }
+
+    /// Snapshot the full machine state (CPU, memory, peripherals,
+    /// cartridge) into a self-contained buffer, prefixed with a magic
+    /// tag and version so `load_state` can reject a stale or foreign one.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(savestate::MAGIC);
+        out.push(savestate::VERSION);
+        self.cpu.write_state(&mut out);
+        out
+    }
+
+    /// Restore a snapshot produced by `save_state`.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut cursor = Cursor::new(data);
+        if cursor.read_bytes(savestate::MAGIC.len())? != savestate::MAGIC {
+            return Err("savestate: bad magic".to_string());
+        }
+        let version = cursor.read_u8()?;
+        if version != savestate::VERSION {
+            return Err(format!(
+                "savestate: unsupported version {} (expected {})",
+                version,
+                savestate::VERSION
+            ));
+        }
+        self.cpu.read_state(&mut cursor)
     }
 }
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Debug flag: log every instruction fetched, gated by `Debugger::flags`.
+pub const DBG_CPU: u8 = 0x01;
+/// Debug flag: log every memory read.
+pub const DBG_RDMEM: u8 = 0x02;
+/// Debug flag: log every memory write.
+pub const DBG_WRMEM: u8 = 0x04;
+
+/// Outcome of a single `Cpu::step_debug` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Continued,
+    HitBreakpoint(u16),
+    HitWatchpoint(u16, u8, u8),
+}
+
+/// What kind of memory access a `Watchpoint` should break on.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Value(u8),
+}
+
+pub struct Watchpoint {
+    addr: u16,
+    kind: WatchKind,
+}
+
+/// A user-supplied callback fired when a given PC or memory cell is
+/// touched. Returning `Some(value)` overrides the value the caller
+/// would otherwise have seen (read hooks) or written (write hooks).
+pub type Hook = Box<dyn FnMut(u16, u8) -> Option<u8>>;
+
+/// Snapshot of the CPU state, returned by `Cpu::register_dump` for the
+/// debugger's own console/UI to render.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct RegisterDump {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+/// Optional layer around the `execute` loop: PC breakpoints, memory
+/// watchpoints, and address-keyed access hooks. Kept separate from `Cpu`
+/// and `Memory` so that running without a debugger attached costs nothing
+/// beyond a couple of `Option`/`is_empty` checks.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: HashSet<u16>,
+    /// Opcodes to break before executing, regardless of PC: e.g. the
+    /// `(HL)`-operand `SET`/`RES` CB opcodes.
+    opcode_breakpoints: HashSet<u8>,
+    watchpoints: Vec<Watchpoint>,
+    hooks: HashMap<u16, Hook>,
+    /// `DBG_CPU | DBG_RDMEM | DBG_WRMEM`-style logging mask.
+    flags: u8,
+    last_hit: Option<(u16, u8, u8)>,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn break_on_opcode(&mut self, opcode: u8) {
+        self.opcode_breakpoints.insert(opcode);
+    }
+
+    pub fn remove_opcode_breakpoint(&mut self, opcode: u8) {
+        self.opcode_breakpoints.remove(&opcode);
+    }
+
+    pub fn is_opcode_breakpoint(&self, opcode: u8) -> bool {
+        self.opcode_breakpoints.contains(&opcode)
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.flags = flags;
+    }
+
+    pub fn flag_enabled(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn watch_read(&mut self, addr: u16) {
+        self.watchpoints.push(Watchpoint { addr, kind: WatchKind::Read });
+    }
+
+    pub fn watch_write(&mut self, addr: u16) {
+        self.watchpoints.push(Watchpoint { addr, kind: WatchKind::Write });
+    }
+
+    pub fn watch_value(&mut self, addr: u16, value: u8) {
+        self.watchpoints.push(Watchpoint { addr, kind: WatchKind::Value(value) });
+    }
+
+    /// Returns whether any watchpoint fires for this access, recording
+    /// `(addr, old, new)` for `take_last_hit` when one does.
+    pub fn check_watchpoint(&mut self, addr: u16, old: u8, new: u8, is_write: bool) -> bool {
+        let hit = self.watchpoints.iter().any(|w| {
+            if w.addr != addr {
+                return false;
+            }
+            match w.kind {
+                WatchKind::Read => !is_write,
+                WatchKind::Write => is_write,
+                WatchKind::Value(v) => new == v,
+            }
+        });
+        if hit {
+            self.last_hit = Some((addr, old, new));
+        }
+        hit
+    }
+
+    /// Returns and clears the `(addr, old, new)` of the most recent
+    /// watchpoint hit, for callers building a `StepResult`.
+    pub fn take_last_hit(&mut self) -> Option<(u16, u8, u8)> {
+        self.last_hit.take()
+    }
+
+    pub fn add_hook(&mut self, addr: u16, hook: Hook) {
+        self.hooks.insert(addr, hook);
+    }
+
+    /// Runs the hook registered at `addr`, if any, letting it override
+    /// the value that was about to be returned/written.
+    pub fn run_hook(&mut self, addr: u16, value: u8) -> Option<u8> {
+        match self.hooks.get_mut(&addr) {
+            Some(hook) => hook(addr, value),
+            None => None,
+        }
+    }
+}
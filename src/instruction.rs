@@ -0,0 +1,158 @@
+use std::fmt;
+
+use crate::cpu::reg_name;
+
+/// An 8-bit register, in the order the LR35902 opcode encoding indexes
+/// them (skipping the `(HL)` slot — see `Target`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum R {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    A,
+}
+
+/// Where an instruction's operand lives: a register, or the byte at
+/// `(HL)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Reg(R),
+    MemHL,
+}
+
+impl Target {
+    /// The `opcode & 0x07` / `opcode >> 3` index the LR35902 encoding
+    /// uses for `[B, C, D, E, H, L, (HL), A]`.
+    pub fn from_index(index: u8) -> Target {
+        match index & 0x07 {
+            0 => Target::Reg(R::B),
+            1 => Target::Reg(R::C),
+            2 => Target::Reg(R::D),
+            3 => Target::Reg(R::E),
+            4 => Target::Reg(R::H),
+            5 => Target::Reg(R::L),
+            6 => Target::MemHL,
+            _ => Target::Reg(R::A),
+        }
+    }
+
+    pub fn index(&self) -> u8 {
+        match self {
+            Target::Reg(R::B) => 0,
+            Target::Reg(R::C) => 1,
+            Target::Reg(R::D) => 2,
+            Target::Reg(R::E) => 3,
+            Target::Reg(R::H) => 4,
+            Target::Reg(R::L) => 5,
+            Target::MemHL => 6,
+            Target::Reg(R::A) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", reg_name(self.index()))
+    }
+}
+
+/// A JR/JP/CALL/RET branch condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Always => Ok(()),
+            Condition::NZ => write!(f, "NZ"),
+            Condition::Z => write!(f, "Z"),
+            Condition::NC => write!(f, "NC"),
+            Condition::C => write!(f, "C"),
+        }
+    }
+}
+
+/// A decoded instruction: the result of turning the bytes at `pc` into a
+/// typed value without touching any CPU/memory state. `Cpu::decode`
+/// produces these; `Cpu::execute_instruction` runs them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Rlc(Target),
+    Rrc(Target),
+    Rl(Target),
+    Rr(Target),
+    Sla(Target),
+    Sra(Target),
+    Swap(Target),
+    Srl(Target),
+    Bit(u8, Target),
+    Res(u8, Target),
+    Set(u8, Target),
+    AddImm(u8),
+    Jr(Condition, i8),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Rlc(t) => write!(f, "RLC {}", t),
+            Instruction::Rrc(t) => write!(f, "RRC {}", t),
+            Instruction::Rl(t) => write!(f, "RL {}", t),
+            Instruction::Rr(t) => write!(f, "RR {}", t),
+            Instruction::Sla(t) => write!(f, "SLA {}", t),
+            Instruction::Sra(t) => write!(f, "SRA {}", t),
+            Instruction::Swap(t) => write!(f, "SWAP {}", t),
+            Instruction::Srl(t) => write!(f, "SRL {}", t),
+            Instruction::Bit(b, t) => write!(f, "BIT {},{}", b, t),
+            Instruction::Res(b, t) => write!(f, "RES {},{}", b, t),
+            Instruction::Set(b, t) => write!(f, "SET {},{}", b, t),
+            Instruction::AddImm(n) => write!(f, "ADD A,${:02X}", n),
+            Instruction::Jr(Condition::Always, offset) => write!(f, "JR {}", offset),
+            Instruction::Jr(cond, offset) => write!(f, "JR {},{}", cond, offset),
+        }
+    }
+}
+
+/// Decode a 0xCB-prefixed opcode into its typed `Instruction`. Pure: it
+/// only looks at the opcode byte, never at registers or memory.
+pub fn decode_cb(cb_op: u8) -> Instruction {
+    let target = Target::from_index(cb_op & 0x07);
+    let bit = (cb_op >> 3) & 0x07;
+
+    match cb_op >> 6 {
+        0 => match bit {
+            0 => Instruction::Rlc(target),
+            1 => Instruction::Rrc(target),
+            2 => Instruction::Rl(target),
+            3 => Instruction::Rr(target),
+            4 => Instruction::Sla(target),
+            5 => Instruction::Sra(target),
+            6 => Instruction::Swap(target),
+            _ => Instruction::Srl(target),
+        },
+        1 => Instruction::Bit(bit, target),
+        2 => Instruction::Res(bit, target),
+        _ => Instruction::Set(bit, target),
+    }
+}
+
+/// Decode a JR opcode's condition from its main-page opcode byte.
+/// Callers must only pass `0x18`/`0x20`/`0x28`/`0x30`/`0x38`.
+pub fn jr_condition(opcode: u8) -> Condition {
+    match opcode {
+        0x20 => Condition::NZ,
+        0x28 => Condition::Z,
+        0x30 => Condition::NC,
+        0x38 => Condition::C,
+        _ => Condition::Always,
+    }
+}
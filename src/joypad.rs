@@ -1,3 +1,5 @@
+use crate::savestate::{Cursor, SaveState};
+
 pub const JOYPAD_INTERRUPT: u8 = 0x10;
 
 pub enum ButtonSelection {
@@ -56,6 +58,10 @@ impl JoypadState {
         self.inter
     }
 
+    pub fn clear_interrupt(&mut self) {
+        self.inter = 0;
+    }
+
     fn bit_a(&self) -> u8 {
         if self.buttons[A_IDX] {
             0
@@ -121,6 +127,32 @@ impl JoypadState {
     }
 }
 
+impl SaveState for JoypadState {
+    fn write_state(&self, out: &mut Vec<u8>) {
+        let mut packed = 0u8;
+        for (i, pressed) in self.buttons.iter().enumerate() {
+            if *pressed {
+                packed |= 1 << i;
+            }
+        }
+        out.push(packed);
+        out.push(matches!(self.button_selection, ButtonSelection::Direction) as u8);
+    }
+
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String> {
+        let packed = input.read_u8()?;
+        for i in 0..self.buttons.len() {
+            self.buttons[i] = (packed & (1 << i)) != 0;
+        }
+        self.button_selection = if input.read_bool()? {
+            ButtonSelection::Direction
+        } else {
+            ButtonSelection::Action
+        };
+        Ok(())
+    }
+}
+
 #[test]
 fn test_read_write() {
     // setup initial state
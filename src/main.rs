@@ -1,11 +1,17 @@
 use console::Console;
 
+mod apu;
+mod bus;
 mod cartridge;
 mod console;
 mod cpu;
+mod debugger;
+mod instruction;
 mod joypad;
 mod memory;
 mod ppu;
+mod savestate;
+mod serial;
 mod timer;
 
 fn main() {
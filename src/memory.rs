@@ -1,12 +1,31 @@
+use log::trace;
+
 use crate::{
+    apu::Apu,
     cartridge::{Cartridge, NoCartridge},
+    debugger::{Debugger, DBG_RDMEM, DBG_WRMEM},
     joypad::JoypadState,
     ppu::Ppu,
+    savestate::{Cursor, SaveState},
+    serial::Serial,
     timer::Timer,
 };
 
 const RAM_SIZE: usize = 8_192;
 const HRAM_SIZE: usize = 127;
+const OAM_SIZE: u8 = 0xA0;
+
+/// An OAM DMA transfer in flight: a 0xFF46 write latches this rather
+/// than copying all `OAM_SIZE` bytes immediately, so `dma_tick` can drain
+/// it one byte per M-cycle instead.
+#[derive(Default)]
+struct DmaState {
+    base: u8,
+    remaining_cycles: u8,
+    remaining_delay: u8,
+}
+
+const BOOT_ROM_SIZE: usize = 0x100;
 
 pub struct Memory {
     ram: [u8; RAM_SIZE],
@@ -14,7 +33,19 @@ pub struct Memory {
     cartridge: Box<dyn Cartridge>,
     joy: JoypadState,
     timer: Timer,
+    serial: Serial,
+    apu: Apu,
     ppu: Ppu,
+    dma: DmaState,
+    /// While `Some`, overlays the cartridge at 0x0000-0x00FF with the DMG
+    /// boot ROM; a nonzero write to 0xFF50 unmaps it for good.
+    boot: Option<[u8; BOOT_ROM_SIZE]>,
+    debugger: Option<Debugger>,
+    break_hit: bool,
+    /// IF (0xFF0F): latched, not-yet-serviced interrupt requests.
+    interrupt_flags: u8,
+    /// IE (0xFFFF): which of the five sources the CPU will act on.
+    interrupt_enable: u8,
 }
 
 impl Default for Memory {
@@ -22,17 +53,81 @@ impl Default for Memory {
         Self {
             ram: [0; RAM_SIZE],
             hram: [0; HRAM_SIZE],
-            cartridge: Box::new(NoCartridge {}),
+            cartridge: Box::new(NoCartridge::default()),
             joy: Default::default(),
             timer: Default::default(),
+            serial: Default::default(),
+            apu: Default::default(),
             ppu: Default::default(),
+            dma: Default::default(),
+            boot: None,
+            debugger: None,
+            break_hit: false,
+            interrupt_flags: 0,
+            interrupt_enable: 0,
         }
     }
 }
 
 impl Memory {
-    pub fn read8(&self, addr: u16) -> u8 {
+    /// Attach a debugger; memory accesses are then checked against its
+    /// watchpoints and hooks. Pass `None` to detach it again.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    /// Returns whether a watchpoint fired since the last call, clearing
+    /// the flag.
+    pub fn take_break_hit(&mut self) -> bool {
+        let hit = self.break_hit;
+        self.break_hit = false;
+        hit
+    }
+
+    /// Read without side effects: does not trip watchpoints or hooks.
+    /// Used by the disassembler, which must not disturb the machine
+    /// state it is describing.
+    pub fn peek8(&self, addr: u16) -> u8 {
+        self.read8_raw(addr)
+    }
+
+    pub fn peek16(&self, addr: u16) -> u16 {
+        let lb = self.peek8(addr) as u16;
+        let hb = self.peek8(addr.wrapping_add(1)) as u16;
+        (hb << 8) | lb
+    }
+
+    pub fn read8(&mut self, addr: u16) -> u8 {
+        if self.dma_blocks(addr) {
+            return 0xFF;
+        }
+
+        let val = self.read8_raw(addr);
+        match &mut self.debugger {
+            Some(dbg) => {
+                if dbg.flag_enabled(DBG_RDMEM) {
+                    trace!("RD {:04X} = {:02X}", addr, val);
+                }
+                if dbg.check_watchpoint(addr, val, val, false) {
+                    self.break_hit = true;
+                }
+                dbg.run_hook(addr, val).unwrap_or(val)
+            }
+            None => val,
+        }
+    }
+
+    fn read8_raw(&self, addr: u16) -> u8 {
         match addr {
+            0x0000..=0x00FF if self.boot.is_some() => self.boot.as_ref().unwrap()[addr as usize],
             0x0000..=0x7FFF => self.cartridge.read_rom(addr),
             0x8000..=0x9FFF => self.ppu.read_vram(addr),
             0xA000..=0xBFFF => self.cartridge.read_ram(addr),
@@ -44,6 +139,10 @@ impl Memory {
             0xFF05 => self.timer.read_tima(),
             0xFF06 => self.timer.read_tma(),
             0xFF07 => self.timer.read_tac(),
+            0xFF01 => self.serial.read_sb(),
+            0xFF02 => self.serial.read_sc(),
+            0xFF0F => self.interrupt_flags | 0xE0,
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.read(addr),
             0xFF40 => self.ppu.read_control(),
             0xFF41 => self.ppu.read_status(),
             0xFF42 => self.ppu.read_scy(),
@@ -57,11 +156,33 @@ impl Memory {
             0xFF4A => self.ppu.read_wy(),
             0xFF4B => self.ppu.read_wx(),
             0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80],
+            0xFFFF => self.interrupt_enable,
             _ => 0xFF,
         }
     }
 
     pub fn write8(&mut self, addr: u16, val: u8) {
+        if self.dma_blocks(addr) {
+            return;
+        }
+
+        let old = self.read8_raw(addr);
+        let val = match &mut self.debugger {
+            Some(dbg) => {
+                if dbg.flag_enabled(DBG_WRMEM) {
+                    trace!("WR {:04X} = {:02X}", addr, val);
+                }
+                if dbg.check_watchpoint(addr, old, val, true) {
+                    self.break_hit = true;
+                }
+                dbg.run_hook(addr, val).unwrap_or(val)
+            }
+            None => val,
+        };
+        self.write8_raw(addr, val);
+    }
+
+    fn write8_raw(&mut self, addr: u16, val: u8) {
         match addr {
             0x0000..=0x7FFF => self.cartridge.write_rom(addr, val),
             0x8000..=0x9FFF => self.ppu.write_vram(addr, val),
@@ -74,23 +195,33 @@ impl Memory {
             0xFF05 => self.timer.write_tima(val),
             0xFF06 => self.timer.write_tma(val),
             0xFF07 => self.timer.write_tac(val),
+            0xFF01 => self.serial.write_sb(val),
+            0xFF02 => self.serial.write_sc(val),
+            0xFF0F => self.interrupt_flags = val & 0x1F,
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write(addr, val),
             0xFF40 => self.ppu.write_control(val),
             0xFF41 => self.ppu.write_status(val),
             0xFF42 => self.ppu.write_scy(val),
             0xFF43 => self.ppu.write_scx(val),
             0xFF45 => self.ppu.write_lyc(val),
-            0xFF46 => self.oam_dma(val),
+            0xFF46 => self.start_oam_dma(val),
             0xFF47 => self.ppu.write_bgp(val),
             0xFF48 => self.ppu.write_obp0(val),
             0xFF49 => self.ppu.write_obp1(val),
             0xFF4A => self.ppu.write_wy(val),
             0xFF4B => self.ppu.write_wx(val),
+            0xFF50 => {
+                if val != 0 {
+                    self.boot = None;
+                }
+            }
             0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80] = val,
+            0xFFFF => self.interrupt_enable = val,
             _ => (),
         }
     }
 
-    pub fn read16(&self, addr: u16) -> u16 {
+    pub fn read16(&mut self, addr: u16) -> u16 {
         let lb = self.read8(addr) as u16;
         let hb = self.read8(addr + 1) as u16;
         (hb << 8) | lb
@@ -104,11 +235,276 @@ impl Memory {
         self.write8(addr + 1, hb);
     }
 
-    fn oam_dma(&mut self, value: u8) {
-        let source_address = (value as u16) << 8;
-        for offset in 0..0xA0 {
-            let byte = self.read8(source_address + offset);
-            self.write8(0xFE00 + offset, byte);
+    pub fn set_serial_transport(&mut self, transport: Box<dyn crate::serial::Transport>) {
+        self.serial.set_transport(transport);
+    }
+
+    /// Swap in a cartridge loaded from a ROM image, replacing whatever is
+    /// currently mapped at 0x0000-0x7FFF/0xA000-0xBFFF (by default a
+    /// `NoCartridge`).
+    pub fn set_cartridge(&mut self, cartridge: Box<dyn Cartridge>) {
+        self.cartridge = cartridge;
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.cartridge.has_battery()
+    }
+
+    pub fn dump_ram(&self) -> &[u8] {
+        self.cartridge.dump_ram()
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.cartridge.load_ram(data)
+    }
+
+    /// Map a 256-byte DMG boot ROM in over the cartridge at 0x0000-0x00FF
+    /// until software disables it via a 0xFF50 write; see `read8_raw`.
+    pub fn set_boot_rom(&mut self, rom: [u8; BOOT_ROM_SIZE]) {
+        self.boot = Some(rom);
+    }
+
+    /// Turn hardware-faithful VRAM/OAM access gating on or off; see
+    /// `Ppu::set_strict_timing`.
+    pub fn set_strict_timing(&mut self, enabled: bool) {
+        self.ppu.set_strict_timing(enabled);
+    }
+
+    /// Pick which built-in `Color -> RGBA` table `render_rgba` uses.
+    pub fn set_color_theme(&mut self, theme: crate::ppu::ColorTheme) {
+        self.ppu.set_color_theme(theme);
+    }
+
+    /// Render the current frame as RGBA for a window backend to blit
+    /// directly; see `Ppu::render_rgba`.
+    pub fn render_rgba(&self, out: &mut [u8; crate::ppu::FRAME_SIZE]) {
+        self.ppu.render_rgba(out);
+    }
+
+    /// Advance the timer, serial and APU subsystems by `ticks` M-cycles,
+    /// latching any interrupt each of them raises into IF. The APU's
+    /// frame sequencer isn't driven by its own cycle count: it steps
+    /// once per falling edge of DIV bit 4, which `Timer::cycle` reports.
+    pub fn cycle(&mut self, ticks: u32) {
+        // The timer, serial port, APU channel timers and PPU all count
+        // T-cycles (dots), four to each M-cycle.
+        let t_cycles = ticks * 4;
+        let apu_steps = self.timer.cycle(t_cycles);
+        for _ in 0..apu_steps {
+            self.apu.step();
+        }
+        self.apu.cycle(t_cycles);
+        self.serial.cycle(t_cycles);
+        self.ppu.cycle(t_cycles);
+        self.latch_interrupts();
+    }
+
+    /// Mix the APU's four channels down to a stereo sample pair, for a
+    /// host to pull audio from.
+    pub fn sample(&self) -> (f32, f32) {
+        self.apu.sample()
+    }
+
+    /// OR each peripheral's pending interrupt bit into IF, then clear it
+    /// at the source so it is only latched once.
+    fn latch_interrupts(&mut self) {
+        self.interrupt_flags |= self.ppu.interrupt();
+        self.ppu.clear_interrupt();
+        self.interrupt_flags |= self.timer.interrupt();
+        self.timer.clear_interrupt();
+        self.interrupt_flags |= self.serial.interrupt();
+        self.serial.clear_interrupt();
+        self.interrupt_flags |= self.joy.interrupt();
+        self.joy.clear_interrupt();
+    }
+
+    /// IE & IF: the set of interrupt sources the CPU should currently
+    /// act on.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flags
+    }
+
+    /// Mark `bit` of IF as serviced.
+    pub fn clear_interrupt_flag(&mut self, bit: u8) {
+        self.interrupt_flags &= !bit;
+    }
+
+    /// Whether the CPU-facing bus is contended by an in-flight OAM DMA:
+    /// while a transfer is running, only HRAM (0xFF80-0xFFFE) stays
+    /// reachable.
+    fn dma_blocks(&self, addr: u16) -> bool {
+        self.is_dma_active() && !(0xFF80..=0xFFFE).contains(&addr)
+    }
+
+    /// Whether an OAM DMA transfer is currently copying bytes; see
+    /// `dma_tick`.
+    pub fn is_dma_active(&self) -> bool {
+        self.dma.remaining_delay > 0 || self.dma.remaining_cycles > 0
+    }
+
+    /// A 0xFF46 write latches a transfer rather than copying `OAM_SIZE`
+    /// bytes immediately; see `dma_tick`.
+    fn start_oam_dma(&mut self, base: u8) {
+        self.dma = DmaState {
+            base,
+            remaining_cycles: OAM_SIZE,
+            remaining_delay: 2,
+        };
+    }
+
+    /// Advance an in-flight OAM DMA transfer by one M-cycle: drain the
+    /// 2-cycle startup delay, then copy exactly one byte per call from
+    /// `(base << 8) + offset` to `0xFE00 + offset`. A no-op once the
+    /// transfer has finished.
+    pub fn dma_tick(&mut self) {
+        if self.dma.remaining_delay > 0 {
+            self.dma.remaining_delay -= 1;
+            return;
+        }
+        if self.dma.remaining_cycles == 0 {
+            return;
         }
+
+        let offset = (OAM_SIZE - self.dma.remaining_cycles) as u16;
+        let byte = self.read8_raw(((self.dma.base as u16) << 8) + offset);
+        self.ppu.write_oam_dma(0xFE00 + offset, byte);
+        self.dma.remaining_cycles -= 1;
+    }
+}
+
+impl SaveState for Memory {
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ram);
+        out.extend_from_slice(&self.hram);
+        out.push(self.interrupt_flags);
+        out.push(self.interrupt_enable);
+        self.timer.write_state(out);
+        self.joy.write_state(out);
+        self.ppu.write_state(out);
+        self.apu.write_state(out);
+        self.cartridge.write_state(out);
+    }
+
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String> {
+        self.ram.copy_from_slice(input.read_bytes(RAM_SIZE)?);
+        self.hram.copy_from_slice(input.read_bytes(HRAM_SIZE)?);
+        self.interrupt_flags = input.read_u8()?;
+        self.interrupt_enable = input.read_u8()?;
+        self.timer.read_state(input)?;
+        self.joy.read_state(input)?;
+        self.ppu.read_state(input)?;
+        self.apu.read_state(input)?;
+        self.cartridge.read_state(input)?;
+        Ok(())
+    }
+}
+
+impl crate::bus::Bus for Memory {
+    fn read8(&mut self, addr: u16) -> u8 {
+        Memory::read8(self, addr)
+    }
+
+    fn write8(&mut self, addr: u16, val: u8) {
+        Memory::write8(self, addr, val)
+    }
+
+    fn read16(&mut self, addr: u16) -> u16 {
+        Memory::read16(self, addr)
+    }
+
+    fn write16(&mut self, addr: u16, val: u16) {
+        Memory::write16(self, addr, val)
+    }
+
+    fn peek8(&self, addr: u16) -> u8 {
+        Memory::peek8(self, addr)
+    }
+
+    fn peek16(&self, addr: u16) -> u16 {
+        Memory::peek16(self, addr)
+    }
+
+    fn cycle(&mut self, ticks: u32) {
+        Memory::cycle(self, ticks)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        Memory::pending_interrupts(self)
+    }
+
+    fn clear_interrupt_flag(&mut self, bit: u8) {
+        Memory::clear_interrupt_flag(self, bit)
+    }
+
+    fn set_serial_transport(&mut self, transport: Box<dyn crate::serial::Transport>) {
+        Memory::set_serial_transport(self, transport)
+    }
+
+    fn set_cartridge(&mut self, cartridge: Box<dyn Cartridge>) {
+        Memory::set_cartridge(self, cartridge)
+    }
+
+    fn set_boot_rom(&mut self, rom: [u8; 0x100]) {
+        Memory::set_boot_rom(self, rom)
+    }
+
+    fn set_strict_timing(&mut self, enabled: bool) {
+        Memory::set_strict_timing(self, enabled)
+    }
+
+    fn set_color_theme(&mut self, theme: crate::ppu::ColorTheme) {
+        Memory::set_color_theme(self, theme)
+    }
+
+    fn render_rgba(&self, out: &mut [u8; crate::ppu::FRAME_SIZE]) {
+        Memory::render_rgba(self, out)
+    }
+
+    fn dma_tick(&mut self) {
+        Memory::dma_tick(self)
+    }
+
+    fn is_dma_active(&self) -> bool {
+        Memory::is_dma_active(self)
+    }
+
+    fn has_battery(&self) -> bool {
+        Memory::has_battery(self)
+    }
+
+    fn dump_ram(&self) -> &[u8] {
+        Memory::dump_ram(self)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        Memory::load_ram(self, data)
+    }
+
+    fn sample(&self) -> (f32, f32) {
+        Memory::sample(self)
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        SaveState::write_state(self, out)
+    }
+
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String> {
+        SaveState::read_state(self, input)
+    }
+
+    fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        Memory::set_debugger(self, debugger)
+    }
+
+    fn debugger(&self) -> Option<&Debugger> {
+        Memory::debugger(self)
+    }
+
+    fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        Memory::debugger_mut(self)
+    }
+
+    fn take_break_hit(&mut self) -> bool {
+        Memory::take_break_hit(self)
     }
 }
@@ -1,8 +1,9 @@
-use std::{f32::consts::PI, thread::current};
+use std::collections::VecDeque;
+
+use crate::savestate::{write_bool, write_u16, Cursor, SaveState};
 
 const OAM_SEARCH_CYCLES: u32 = 80;
-const PIXEL_CYCLES: u32 = 172;
-const HBLANK_CYCLES: u32 = 204;
+const LINE_CYCLES: u32 = 456;
 const TILE_SIZE: u8 = 8;
 
 const COLS: usize = 160;
@@ -12,6 +13,10 @@ const VBLANK_ROWS: usize = 10;
 const VRAM_SIZE: usize = 8_192;
 const OAM_SIZE: usize = 160;
 
+/// Byte size of the RGBA buffer `render_rgba` fills: one `[u8; 4]` pixel
+/// per framebuffer entry.
+pub const FRAME_SIZE: usize = COLS * ROWS * 4;
+
 pub const PPU_VBLANK_INTERRUPT: u8 = 0x01;
 pub const PPU_STAT_INTERRUPT: u8 = 0x02;
 
@@ -54,22 +59,89 @@ fn test_from_int() {
     assert!(matches!(Color::from_int(0b1001_1000), Color::White));
 }
 
+/// Which palette a framebuffer entry's 2-bit index should be looked up
+/// in: `step_bg_fetcher` always tags `Background`, `fetch_sprite` tags
+/// `Sprite1`/`Sprite2` depending on the OBP0/OBP1 select bit.
+#[derive(Copy, Clone, PartialEq)]
+enum PaletteSource {
+    Background,
+    Sprite1,
+    Sprite2,
+}
+
+/// A built-in `Color -> RGBA` lookup table for `render_rgba`; see
+/// `set_color_theme`.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorTheme {
+    /// Classic 4-shade grayscale.
+    Grayscale,
+    /// The green-tinted LCD palette real DMG units shipped with.
+    GreenLcd,
+}
+
+impl ColorTheme {
+    fn rgba(self, color: Color) -> [u8; 4] {
+        match self {
+            ColorTheme::Grayscale => match color {
+                Color::White => [0xFF, 0xFF, 0xFF, 0xFF],
+                Color::LGray => [0xB6, 0xB6, 0xB6, 0xFF],
+                Color::DGray => [0x67, 0x67, 0x67, 0xFF],
+                Color::Black => [0x00, 0x00, 0x00, 0xFF],
+            },
+            ColorTheme::GreenLcd => match color {
+                Color::White => [0xE3, 0xEE, 0xC0, 0xFF],
+                Color::LGray => [0xAE, 0xBA, 0x89, 0xFF],
+                Color::DGray => [0x5E, 0x67, 0x45, 0xFF],
+                Color::Black => [0x20, 0x20, 0x20, 0xFF],
+            },
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 struct SpriteInfo {
     y: u8,
     x: u8,
-    index: u8,
+    tile: u8,
+    /// Attribute byte (OAM byte 3): bit 7 = OBJ-behind-BG priority, bit 6
+    /// = Y-flip, bit 5 = X-flip, bit 4 = palette select.
+    flags: u8,
+}
+
+/// One pixel a sprite fetch overlaid into the sprite FIFO: its 2-bit
+/// color index, which OBP palette it came from, and whether it yields to
+/// a non-zero background pixel (OAM attribute bit 7).
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    color: u8,
+    source: PaletteSource,
+    behind_bg: bool,
+}
+
+/// Which two-dot step the background/window tile fetcher is on; see
+/// `step_bg_fetcher`.
+#[derive(Copy, Clone, PartialEq)]
+enum FetchStep {
+    FetchTileNumber,
+    FetchDataLow,
+    FetchDataHigh,
+    Push,
 }
 
 pub struct Ppu {
     vram: [u8; VRAM_SIZE],
     oam: [u8; OAM_SIZE],
     data: [u8; COLS * ROWS],
+    /// Which palette each `data` entry was drawn from, so `render_rgba`
+    /// can map it with the right one.
+    sources: [PaletteSource; COLS * ROWS],
     sprites: [SpriteInfo; 10],
+    /// How many of `sprites` were filled in by the last `oam_search`.
+    sprite_count: u8,
     ticks: u32,
     bg_palette: [Color; 4],
-    sprite_palette1: [Color; 3],
-    sprite_palette2: [Color; 3],
+    sprite_palette1: [Color; 4],
+    sprite_palette2: [Color; 4],
     win_tile_map_addr: u16,
     bg_tile_data_addr: u16,
     bg_tile_map_addr: u16,
@@ -81,6 +153,9 @@ pub struct Ppu {
     scx: u8,
     winx: u8,
     winy: u8,
+    /// The window's own internal scanline counter: only advances on lines
+    /// the window was actually drawn on, so it stays independent of `ly`.
+    window_line: u8,
     inter: u8,
     lcd_display_enabled: bool,
     win_enable: bool,
@@ -90,6 +165,48 @@ pub struct Ppu {
     vblank_interrupt_enabled: bool,
     oam_interrupt_enabled: bool,
     lyc_interrupt_enabled: bool,
+    /// Whether VRAM/OAM accesses are blocked while the PPU owns them
+    /// (`Transfering` for VRAM, `SearchOam`/`Transfering` for OAM), as
+    /// real hardware does. On by default; a host can turn it off to trade
+    /// accuracy for leniency with buggy ROMs.
+    strict_timing: bool,
+    /// The `Color -> RGBA` table `render_rgba` looks colors up through.
+    color_theme: ColorTheme,
+    /// Pending background/window pixels, each tagged with the palette
+    /// they'll map through; `step_pixel_pipeline` shifts one out per dot
+    /// once this holds more than a tile's worth.
+    bg_fifo: VecDeque<(u8, PaletteSource)>,
+    /// Sprite overlay for the same columns as `bg_fifo`, kept exactly the
+    /// same length at all times (`None` = no sprite claimed that column).
+    sprite_fifo: VecDeque<Option<SpritePixel>>,
+    /// Next screen column `step_pixel_pipeline` will emit a pixel to.
+    lx: u8,
+    /// Pixels still to discard from the first tile this line, for fine
+    /// (sub-tile) SCX scroll.
+    scx_discard: u8,
+    fetch_step: FetchStep,
+    /// Dots left before the current fetch step completes.
+    fetch_dot: u8,
+    /// How many background/window tiles have been pushed this line.
+    fetch_col: u8,
+    /// How many window tiles have been pushed this line, independent of
+    /// `fetch_col` so the window always starts from its own tile column 0.
+    window_col: u8,
+    fetch_tile_idx: u8,
+    fetch_byte1: u8,
+    fetch_byte2: u8,
+    /// Whether the tile fetcher is currently pulling from the window
+    /// tile map rather than the scrolled background.
+    fetch_window: bool,
+    /// Whether the window was actually fetched at least once this line,
+    /// so `window_line` only advances on lines it was drawn on.
+    window_drawn: bool,
+    /// Dots left to stall the background fetcher while a sprite fetch
+    /// (triggered by `oam_search`'s results) is in flight.
+    sprite_stall: u8,
+    /// Which of this line's `sprites` have already had their fetch
+    /// triggered, so a sprite is only fetched once per line.
+    sprite_consumed: [bool; 10],
 }
 
 impl Default for Ppu {
@@ -98,15 +215,18 @@ impl Default for Ppu {
             vram: [0; VRAM_SIZE],
             oam: [0; OAM_SIZE],
             data: [0; COLS * ROWS],
+            sources: [PaletteSource::Background; COLS * ROWS],
             sprites: [SpriteInfo {
                 y: 0,
                 x: 0,
-                index: 0,
+                tile: 0,
+                flags: 0,
             }; 10],
+            sprite_count: 0,
             ticks: 0u32,
             bg_palette: [Color::White, Color::LGray, Color::DGray, Color::Black],
-            sprite_palette1: [Color::LGray, Color::DGray, Color::Black],
-            sprite_palette2: [Color::LGray, Color::DGray, Color::Black],
+            sprite_palette1: [Color::White, Color::LGray, Color::DGray, Color::Black],
+            sprite_palette2: [Color::White, Color::LGray, Color::DGray, Color::Black],
             win_tile_map_addr: Default::default(),
             bg_tile_data_addr: Default::default(),
             bg_tile_map_addr: Default::default(),
@@ -118,6 +238,7 @@ impl Default for Ppu {
             scx: Default::default(),
             winx: Default::default(),
             winy: Default::default(),
+            window_line: Default::default(),
             inter: Default::default(),
             lcd_display_enabled: Default::default(),
             win_enable: Default::default(),
@@ -127,6 +248,23 @@ impl Default for Ppu {
             vblank_interrupt_enabled: Default::default(),
             oam_interrupt_enabled: Default::default(),
             lyc_interrupt_enabled: Default::default(),
+            strict_timing: true,
+            color_theme: ColorTheme::Grayscale,
+            bg_fifo: VecDeque::new(),
+            sprite_fifo: VecDeque::new(),
+            lx: 0,
+            scx_discard: 0,
+            fetch_step: FetchStep::FetchTileNumber,
+            fetch_dot: 0,
+            fetch_col: 0,
+            window_col: 0,
+            fetch_tile_idx: 0,
+            fetch_byte1: 0,
+            fetch_byte2: 0,
+            fetch_window: false,
+            window_drawn: false,
+            sprite_stall: 0,
+            sprite_consumed: [false; 10],
         }
     }
 }
@@ -252,9 +390,9 @@ impl Ppu {
     }
 
     pub fn read_obp0(&self) -> u8 {
-        let bits23 = (self.sprite_palette1[0] as u8) << 2;
-        let bits45 = (self.sprite_palette1[1] as u8) << 4;
-        let bits67 = (self.sprite_palette1[2] as u8) << 6;
+        let bits23 = (self.sprite_palette1[1] as u8) << 2;
+        let bits45 = (self.sprite_palette1[2] as u8) << 4;
+        let bits67 = (self.sprite_palette1[3] as u8) << 6;
         bits67 | bits45 | bits23
     }
 
@@ -266,9 +404,9 @@ impl Ppu {
     }
 
     pub fn read_obp1(&self) -> u8 {
-        let bits23 = (self.sprite_palette2[0] as u8) << 2;
-        let bits45 = (self.sprite_palette2[1] as u8) << 4;
-        let bits67 = (self.sprite_palette2[2] as u8) << 6;
+        let bits23 = (self.sprite_palette2[1] as u8) << 2;
+        let bits45 = (self.sprite_palette2[2] as u8) << 4;
+        let bits67 = (self.sprite_palette2[3] as u8) << 6;
         bits67 | bits45 | bits23
     }
 
@@ -295,22 +433,58 @@ impl Ppu {
         self.winx = value;
     }
 
+    /// Turn hardware-faithful VRAM/OAM access gating on or off; see
+    /// `strict_timing`.
+    pub fn set_strict_timing(&mut self, enabled: bool) {
+        self.strict_timing = enabled;
+    }
+
     pub fn read_vram(&self, addr: u16) -> u8 {
+        if self.strict_timing && self.mode == Mode::Transfering {
+            return 0xFF;
+        }
+        self.read_vram_raw(addr)
+    }
+
+    /// Read VRAM unconditionally, bypassing the mode-based access gate;
+    /// used by the renderer itself, which must see VRAM while the PPU is
+    /// the one holding it.
+    fn read_vram_raw(&self, addr: u16) -> u8 {
         let local_index = (addr & 0x1FFF) as usize;
         self.vram[local_index]
     }
 
     pub fn write_vram(&mut self, addr: u16, value: u8) {
+        if self.strict_timing && self.mode == Mode::Transfering {
+            return;
+        }
         let local_index = (addr & 0x1FFF) as usize;
         self.vram[local_index] = value;
     }
 
     pub fn read_oam(&self, addr: u16) -> u8 {
+        if self.strict_timing && matches!(self.mode, Mode::SearchOam | Mode::Transfering) {
+            return 0xFF;
+        }
         let local_index = addr as usize - 0xFE00;
         self.oam[local_index]
     }
 
     pub fn write_oam(&mut self, addr: u16, value: u8) {
+        if self.strict_timing && matches!(self.mode, Mode::SearchOam | Mode::Transfering) {
+            return;
+        }
+        self.write_oam_raw(addr, value);
+    }
+
+    /// Write OAM unconditionally, bypassing the mode-based access gate;
+    /// used by an in-flight OAM DMA transfer, which real hardware runs
+    /// regardless of PPU mode.
+    pub fn write_oam_dma(&mut self, addr: u16, value: u8) {
+        self.write_oam_raw(addr, value);
+    }
+
+    fn write_oam_raw(&mut self, addr: u16, value: u8) {
         let local_index = addr as usize - 0xFE00;
         self.oam[local_index] = value;
     }
@@ -319,112 +493,287 @@ impl Ppu {
         self.inter
     }
 
+    pub fn clear_interrupt(&mut self) {
+        self.inter = 0;
+    }
+
     pub fn cycle(&mut self, ticks: u32) {
         if !self.lcd_display_enabled {
             return;
         }
 
-        let mut ticks_left = ticks;
-        while ticks_left > 0 {
-            let current_ticks = if ticks_left >= 80 { 80 } else { ticks_left };
-            self.ticks += current_ticks;
-            ticks_left -= current_ticks;
+        for _ in 0..ticks {
+            self.tick_dot();
+        }
+    }
 
-            if self.ticks >= 456 {
-                self.ticks -= 456;
-                self.ly = (self.ly + 1) % (ROWS + VBLANK_ROWS) as u8;
-                self.check_interrupt_lyc();
+    /// Advance the mode/fetcher/FIFO state machine by a single dot. Unlike
+    /// the old fixed-length mode table, `Transfering`'s length falls out
+    /// of how long the pixel pipeline actually takes to emit 160 pixels
+    /// (sprite fetches and fine scroll included) before `HBlank` starts.
+    fn tick_dot(&mut self) {
+        self.ticks += 1;
 
-                if self.ly >= 144 && self.mode != Mode::VBlank {
-                    self.switch_to_vblank_mode();
+        match self.mode {
+            Mode::SearchOam => {
+                if self.ticks >= OAM_SEARCH_CYCLES {
+                    self.switch_to_transfering_mode();
                 }
             }
-
-            if self.ly < ROWS as u8 {
-                if self.ticks <= OAM_SEARCH_CYCLES {
-                    if self.mode != Mode::SearchOam {
-                        self.switch_to_search_oam_mode();
-                        self.oam_search();
-                    }
-                } else if self.ticks <= OAM_SEARCH_CYCLES + PIXEL_CYCLES {
-                    if self.mode != Mode::Transfering {
-                        self.switch_to_transfering_mode();
-                        self.draw();
-                    }
-                } else {
-                    if self.mode != Mode::HBlank {
-                        self.switch_to_hblank_mode();
-                    }
+            Mode::Transfering => {
+                self.step_pixel_pipeline();
+                if self.lx as usize >= COLS {
+                    self.switch_to_hblank_mode();
                 }
             }
+            Mode::HBlank | Mode::VBlank => {}
+        }
+
+        if self.ticks >= LINE_CYCLES {
+            self.ticks -= LINE_CYCLES;
+            self.ly = (self.ly + 1) % (ROWS + VBLANK_ROWS) as u8;
+            self.check_interrupt_lyc();
+
+            if (self.ly as usize) < ROWS {
+                self.switch_to_search_oam_mode();
+            } else if self.mode != Mode::VBlank {
+                self.switch_to_vblank_mode();
+            }
         }
     }
 
-    fn draw(&mut self) {
-        self.draw_bg();
-        self.draw_sprites();
+    /// Run one dot of the pixel pipeline: stall while a sprite fetch
+    /// triggered by `oam_search`'s results is in flight, start one if the
+    /// current column matches an unconsumed sprite, otherwise step the
+    /// background/window fetcher and, once its FIFO holds more than a
+    /// tile's worth of pixels, shift one out (mixed with the sprite FIFO,
+    /// honoring OBJ-behind-BG priority) to `self.data`/`self.sources`.
+    fn step_pixel_pipeline(&mut self) {
+        if self.sprite_stall > 0 {
+            self.sprite_stall -= 1;
+            return;
+        }
+
+        if self.sprite_enabled {
+            for i in 0..self.sprite_count as usize {
+                if self.sprite_consumed[i] {
+                    continue;
+                }
+                let sprite = self.sprites[i];
+                if sprite.x as i16 - 8 != self.lx as i16 {
+                    continue;
+                }
+                self.sprite_consumed[i] = true;
+                self.fetch_sprite(sprite);
+                self.sprite_stall = 2;
+                return;
+            }
+        }
+
+        self.step_bg_fetcher();
+
+        if self.bg_fifo.len() <= 8 {
+            return;
+        }
+
+        let (bg_color, bg_source) = self.bg_fifo.pop_front().unwrap();
+        let sprite_pixel = self.sprite_fifo.pop_front().flatten();
+
+        if self.scx_discard > 0 {
+            self.scx_discard -= 1;
+            return;
+        }
+
+        let (color, source) = match sprite_pixel {
+            Some(sp) if !(sp.behind_bg && bg_color != 0) => (sp.color, sp.source),
+            _ => (bg_color, bg_source),
+        };
+
+        let dest = self.ly as usize * COLS + self.lx as usize;
+        self.data[dest] = color;
+        self.sources[dest] = source;
+        self.lx += 1;
     }
 
-    fn draw_bg(&mut self) {
-        // if drawing background/window is disabled, we have nothing to do
+    /// Step the background/window tile fetcher one dot. `fetch-tile-number`,
+    /// `fetch-low-byte` and `fetch-high-byte` each take two dots; `push`
+    /// appends the finished tile's 8 pixels to the BG FIFO, padding the
+    /// sprite FIFO alongside it so the two stay the same length. The
+    /// window becomes the fetch source for any tile whose first column
+    /// has reached `winx - 7`, once `win_enable` and `ly >= winy`.
+    fn step_bg_fetcher(&mut self) {
         if !self.bg_window_enable {
+            if self.bg_fifo.is_empty() {
+                for _ in 0..8 {
+                    self.bg_fifo.push_back((0, PaletteSource::Background));
+                    self.sprite_fifo.push_back(None);
+                }
+            }
             return;
         }
 
-        let bg_y = self.ly.wrapping_add(self.scy) as u16;
+        if self.fetch_dot > 0 {
+            self.fetch_dot -= 1;
+            return;
+        }
 
-        for x in 0..COLS {
-            let bg_x = self.scx.wrapping_add(x as u8) as u16;
-            let tile_y = bg_y / TILE_SIZE as u16;
-            let tile_x = bg_x / TILE_SIZE as u16;
-            let tile_idx = self.read_vram(self.bg_tile_map_addr + tile_y * 32 + tile_x);
+        match self.fetch_step {
+            FetchStep::FetchTileNumber => {
+                let window_visible = self.win_enable && self.ly >= self.winy;
+                let window_x_start = self.winx as i16 - 7;
+                let next_x = self.fetch_col as i16 * TILE_SIZE as i16;
+                self.fetch_window = window_visible && next_x >= window_x_start;
+
+                self.fetch_tile_idx = if self.fetch_window {
+                    let win_y = (self.window_line / TILE_SIZE) as u16;
+                    let win_x = self.window_col as u16;
+                    self.read_vram_raw(self.win_tile_map_addr + win_y * 32 + win_x)
+                } else {
+                    let bg_y = (self.ly.wrapping_add(self.scy) / TILE_SIZE) as u16;
+                    let bg_x = ((self.scx / TILE_SIZE) as u16 + self.fetch_col as u16) & 31;
+                    self.read_vram_raw(self.bg_tile_map_addr + bg_y * 32 + bg_x)
+                };
+                self.fetch_step = FetchStep::FetchDataLow;
+                self.fetch_dot = 1;
+            }
+            FetchStep::FetchDataLow => {
+                self.fetch_byte1 = self.read_vram_raw(self.fetch_tile_addr());
+                self.fetch_step = FetchStep::FetchDataHigh;
+                self.fetch_dot = 1;
+            }
+            FetchStep::FetchDataHigh => {
+                self.fetch_byte2 = self.read_vram_raw(self.fetch_tile_addr() + 1);
+                self.fetch_step = FetchStep::Push;
+                self.fetch_dot = 1;
+            }
+            FetchStep::Push => {
+                for bit in (0..8).rev() {
+                    let color = if self.fetch_byte1 & (1 << bit) != 0 {
+                        0b01u8
+                    } else {
+                        0
+                    } | if self.fetch_byte2 & (1 << bit) != 0 {
+                        0b10u8
+                    } else {
+                        0
+                    };
+                    self.bg_fifo.push_back((color, PaletteSource::Background));
+                    self.sprite_fifo.push_back(None);
+                }
+                if self.fetch_window {
+                    self.window_drawn = true;
+                    self.window_col += 1;
+                }
+                self.fetch_col += 1;
+                self.fetch_step = FetchStep::FetchTileNumber;
+                self.fetch_dot = 0;
+            }
+        }
+    }
 
-            let tile_addr = if self.bg_tile_data_addr == 0x8000 {
-                self.bg_tile_data_addr + tile_idx as u16 * 16
-            } else {
-                self.bg_tile_data_addr + (tile_idx as i8 as i16 + 128) as u16 * 16
-            };
+    /// Tile data address for the row the fetcher is currently on, given
+    /// `fetch_tile_idx` and whether it's fetching from the window or the
+    /// scrolled background, using the signed/unsigned addressing LCDC
+    /// bit 4 selects; shared by both the low- and high-byte steps.
+    fn fetch_tile_addr(&self) -> u16 {
+        let row = if self.fetch_window {
+            self.window_line % TILE_SIZE
+        } else {
+            self.ly.wrapping_add(self.scy) % TILE_SIZE
+        };
 
-            let pixel_y = bg_y & 0x07;
-            let byte1 = self.read_vram(tile_addr + (pixel_y * 2));
-            let byte2 = self.read_vram(tile_addr + (pixel_y * 2) + 1);
-
-            let pixel_x = bg_x & 0x07;
-            let color = if byte1 & (1 << pixel_x) != 0 {
-                0b01u8
-            } else {
-                0
-            } | if byte2 & (1 << pixel_x) != 0 {
-                0b10u8
-            } else {
-                0
-            };
-            self.data[self.ly as usize * COLS + x] = color;
+        let tile_addr = if self.bg_tile_data_addr == 0x8000 {
+            self.bg_tile_data_addr + self.fetch_tile_idx as u16 * 16
+        } else {
+            self.bg_tile_data_addr + (self.fetch_tile_idx as i8 as i16 + 128) as u16 * 16
+        };
+        tile_addr + row as u16 * 2
+    }
+
+    /// Fetch one sprite's row and overlay its non-transparent columns
+    /// into the sprite FIFO. `sprite_fifo` is always kept exactly as long
+    /// as `bg_fifo`, so its front 8 slots are already the columns about
+    /// to be emitted; a slot already claimed by an earlier (per
+    /// `oam_search` order) sprite is left alone.
+    fn fetch_sprite(&mut self, sprite: SpriteInfo) {
+        let height = self.sprite_height as i16;
+        let y_flip = sprite.flags & 0x40 != 0;
+        let x_flip = sprite.flags & 0x20 != 0;
+        let behind_bg = sprite.flags & 0x80 != 0;
+        let source = if sprite.flags & 0x10 != 0 {
+            PaletteSource::Sprite2
+        } else {
+            PaletteSource::Sprite1
+        };
+
+        let mut row = self.ly as i16 + 16 - sprite.y as i16;
+        if y_flip {
+            row = height - 1 - row;
         }
 
-        // TODO draw window
-    }
+        let mut tile = sprite.tile;
+        if self.sprite_height == 16 {
+            tile &= 0xFE;
+            if row >= 8 {
+                tile |= 1;
+                row -= 8;
+            }
+        }
 
-    fn draw_sprites(&mut self) {}
+        let tile_addr = 0x8000u16 + tile as u16 * 16 + row as u16 * 2;
+        let byte1 = self.read_vram_raw(tile_addr);
+        let byte2 = self.read_vram_raw(tile_addr + 1);
+
+        let available = self.sprite_fifo.len().min(8);
+        for col in 0..available {
+            if self.sprite_fifo[col].is_some() {
+                continue;
+            }
+            let bit = if x_flip { col as u8 } else { 7 - col as u8 };
+            let color = if byte1 & (1 << bit) != 0 { 0b01u8 } else { 0 }
+                | if byte2 & (1 << bit) != 0 { 0b10u8 } else { 0 };
+            if color == 0 {
+                continue;
+            }
+            self.sprite_fifo[col] = Some(SpritePixel {
+                color,
+                source,
+                behind_bg,
+            });
+        }
+    }
 
     /// Search for the up to 10 first sprites to draw for current line
     fn oam_search(&mut self) {
-        let entries = self
-            .oam
-            .chunks_exact(4)
-            .enumerate()
-            .filter(|(i, e)| self.ly >= e[0] && self.ly < e[0] + self.sprite_height)
-            .take(10);
-
-        let mut arr_idx = 0;
-        for (i, entry) in entries {
-            self.sprites[arr_idx] = SpriteInfo {
+        self.sprite_count = 0;
+        self.sprite_consumed = [false; 10];
+        let height = self.sprite_height as i16;
+
+        for entry in self.oam.chunks_exact(4) {
+            let screen_top = entry[0] as i16 - 16;
+            let on_this_line = self.ly as i16 >= screen_top && (self.ly as i16) < screen_top + height;
+            if !on_this_line {
+                continue;
+            }
+
+            self.sprites[self.sprite_count as usize] = SpriteInfo {
                 y: entry[0],
                 x: entry[1],
-                index: (i * 4) as u8,
+                tile: entry[2],
+                flags: entry[3],
             };
-            arr_idx += 1;
+            self.sprite_count += 1;
+
+            if self.sprite_count as usize == self.sprites.len() {
+                break;
+            }
         }
+
+        // DMG priority between overlapping sprites is smaller X first,
+        // with OAM index as the tie-breaker; a stable sort by `x` gives
+        // exactly that, since the candidates above are already collected
+        // in OAM order.
+        self.sprites[..self.sprite_count as usize].sort_by_key(|s| s.x);
     }
 
     fn check_interrupt_lyc(&mut self) {
@@ -435,6 +784,7 @@ impl Ppu {
 
     fn switch_to_vblank_mode(&mut self) {
         self.mode = Mode::VBlank;
+        self.window_line = 0;
         self.inter |= PPU_VBLANK_INTERRUPT;
         if self.vblank_interrupt_enabled {
             self.inter |= PPU_STAT_INTERRUPT;
@@ -443,19 +793,144 @@ impl Ppu {
 
     fn switch_to_search_oam_mode(&mut self) {
         self.mode = Mode::SearchOam;
+        self.oam_search();
         if self.oam_interrupt_enabled {
             self.inter |= PPU_STAT_INTERRUPT;
         }
     }
 
+    /// Reset the tile fetcher and both pixel FIFOs for a fresh scanline:
+    /// `oam_search` already ran when `SearchOam` was entered, so the
+    /// sprite fetch trigger in `step_pixel_pipeline` can fire as soon as
+    /// `lx` reaches a flagged sprite's column.
     fn switch_to_transfering_mode(&mut self) {
         self.mode = Mode::Transfering;
+        self.bg_fifo.clear();
+        self.sprite_fifo.clear();
+        self.lx = 0;
+        self.scx_discard = self.scx & 7;
+        self.fetch_step = FetchStep::FetchTileNumber;
+        self.fetch_dot = 0;
+        self.fetch_col = 0;
+        self.window_col = 0;
+        self.fetch_window = false;
+        self.window_drawn = false;
     }
 
     fn switch_to_hblank_mode(&mut self) {
         self.mode = Mode::HBlank;
+        // The window's own line counter only advances on lines it was
+        // actually drawn on, so a window that starts mid-screen still
+        // renders from its first tile row.
+        if self.window_drawn {
+            self.window_line += 1;
+        }
         if self.hblank_interrupt_enabled {
             self.inter |= PPU_STAT_INTERRUPT;
         }
     }
+
+    /// Pick which built-in `Color -> RGBA` table `render_rgba` uses.
+    pub fn set_color_theme(&mut self, theme: ColorTheme) {
+        self.color_theme = theme;
+    }
+
+    /// Map the internal 2-bit-index framebuffer through the right palette
+    /// (BG pixels through `bg_palette`, sprite pixels through
+    /// `sprite_palette1`/`sprite_palette2` depending on which one drew
+    /// them) and then through the current color theme, for a window
+    /// backend to blit directly.
+    pub fn render_rgba(&self, out: &mut [u8; FRAME_SIZE]) {
+        for i in 0..COLS * ROWS {
+            let index = self.data[i];
+            let color = match self.sources[i] {
+                PaletteSource::Background => self.bg_palette[index as usize],
+                PaletteSource::Sprite1 => self.sprite_palette1[index as usize],
+                PaletteSource::Sprite2 => self.sprite_palette2[index as usize],
+            };
+            out[i * 4..i * 4 + 4].copy_from_slice(&self.color_theme.rgba(color));
+        }
+    }
+}
+
+impl Mode {
+    fn from_int(value: u8) -> Mode {
+        match value {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::SearchOam,
+            _ => Mode::Transfering,
+        }
+    }
+}
+
+impl SaveState for Ppu {
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.vram);
+        out.extend_from_slice(&self.oam);
+        for color in self.bg_palette.iter() {
+            out.push(*color as u8);
+        }
+        for color in self.sprite_palette1.iter() {
+            out.push(*color as u8);
+        }
+        for color in self.sprite_palette2.iter() {
+            out.push(*color as u8);
+        }
+        write_u16(out, self.win_tile_map_addr);
+        write_u16(out, self.bg_tile_data_addr);
+        write_u16(out, self.bg_tile_map_addr);
+        out.push(self.mode as u8);
+        out.push(self.sprite_height);
+        out.push(self.ly);
+        out.push(self.lyc);
+        out.push(self.scy);
+        out.push(self.scx);
+        out.push(self.winx);
+        out.push(self.winy);
+        out.push(self.window_line);
+        write_bool(out, self.lcd_display_enabled);
+        write_bool(out, self.win_enable);
+        write_bool(out, self.sprite_enabled);
+        write_bool(out, self.bg_window_enable);
+        write_bool(out, self.hblank_interrupt_enabled);
+        write_bool(out, self.vblank_interrupt_enabled);
+        write_bool(out, self.oam_interrupt_enabled);
+        write_bool(out, self.lyc_interrupt_enabled);
+    }
+
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String> {
+        self.vram.copy_from_slice(input.read_bytes(VRAM_SIZE)?);
+        self.oam.copy_from_slice(input.read_bytes(OAM_SIZE)?);
+        for color in self.bg_palette.iter_mut() {
+            *color = Color::from_int(input.read_u8()?);
+        }
+        for color in self.sprite_palette1.iter_mut() {
+            *color = Color::from_int(input.read_u8()?);
+        }
+        for color in self.sprite_palette2.iter_mut() {
+            *color = Color::from_int(input.read_u8()?);
+        }
+        self.win_tile_map_addr = input.read_u16()?;
+        self.bg_tile_data_addr = input.read_u16()?;
+        self.bg_tile_map_addr = input.read_u16()?;
+        self.mode = Mode::from_int(input.read_u8()?);
+        self.sprite_height = input.read_u8()?;
+        self.ly = input.read_u8()?;
+        self.lyc = input.read_u8()?;
+        self.scy = input.read_u8()?;
+        self.scx = input.read_u8()?;
+        self.winx = input.read_u8()?;
+        self.winy = input.read_u8()?;
+        self.window_line = input.read_u8()?;
+        self.lcd_display_enabled = input.read_bool()?;
+        self.win_enable = input.read_bool()?;
+        self.sprite_enabled = input.read_bool()?;
+        self.bg_window_enable = input.read_bool()?;
+        self.hblank_interrupt_enabled = input.read_bool()?;
+        self.vblank_interrupt_enabled = input.read_bool()?;
+        self.oam_interrupt_enabled = input.read_bool()?;
+        self.lyc_interrupt_enabled = input.read_bool()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,82 @@
+//! A lightweight snapshot/restore framework shared by every subsystem that
+//! needs to participate in a savestate: each one appends its fields to the
+//! same `Vec<u8>` on save, and reads them back off the same `Cursor` in the
+//! same order on load, instead of inventing its own framing.
+
+/// Tag written at the start of every snapshot so a truncated or
+/// foreign blob is rejected immediately rather than misread field by
+/// field.
+pub const MAGIC: &[u8; 4] = b"GBZS";
+
+/// Bumped whenever the snapshot layout changes, so a stale snapshot from
+/// an older build is rejected instead of silently desyncing the machine
+/// it's loaded into.
+pub const VERSION: u8 = 1;
+
+/// Appends its fields to a snapshot buffer, and reads them back in the
+/// same order from a `Cursor` over one.
+pub trait SaveState {
+    fn write_state(&self, out: &mut Vec<u8>);
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String>;
+}
+
+pub fn write_u16(out: &mut Vec<u8>, val: u16) {
+    out.push(val as u8);
+    out.push((val >> 8) as u8);
+}
+
+pub fn write_u32(out: &mut Vec<u8>, val: u32) {
+    write_u16(out, val as u16);
+    write_u16(out, (val >> 16) as u16);
+}
+
+pub fn write_bool(out: &mut Vec<u8>, val: bool) {
+    out.push(val as u8);
+}
+
+/// A read-only cursor over a snapshot buffer, tracking how many bytes
+/// have been consumed so far.
+pub struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Cursor { data, pos: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        let val = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| "savestate: unexpected end of data".to_string())?;
+        self.pos += 1;
+        Ok(val)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        Ok(lo | (hi << 8))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let lo = self.read_u16()? as u32;
+        let hi = self.read_u16()? as u32;
+        Ok(lo | (hi << 16))
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err("savestate: unexpected end of data".to_string());
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+}
@@ -0,0 +1,240 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::rc::Rc;
+
+pub const SERIAL_INTERRUPT: u8 = 0x08;
+
+/// Cycles it takes to shift all 8 bits of SB out (and the incoming byte
+/// in) at the Game Boy's internal serial clock.
+const TRANSFER_CYCLES: u32 = 4_096;
+
+/// Blocking half of a serial transport: used when this Game Boy drives
+/// the clock (SC bit 0 set).
+pub trait SyncTransport {
+    fn exchange(&mut self, out: u8) -> u8;
+}
+
+/// Non-blocking half of a serial transport: used when the clock is
+/// driven by the other end (SC bit 0 clear).
+pub trait AsyncTransport {
+    fn poll(&mut self) -> Option<u8>;
+}
+
+pub trait Transport: SyncTransport + AsyncTransport {}
+impl<T: SyncTransport + AsyncTransport> Transport for T {}
+
+/// No link cable plugged in: reads as open bus, never completes an
+/// externally-clocked transfer.
+pub struct NullTransport;
+
+impl SyncTransport for NullTransport {
+    fn exchange(&mut self, _out: u8) -> u8 {
+        0xFF
+    }
+}
+
+impl AsyncTransport for NullTransport {
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Connects two emulator instances over a TCP socket so link-cable ROMs
+/// (trading, multiplayer, printer protocols) work between two running
+/// processes.
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(TcpTransport { stream })
+    }
+}
+
+impl SyncTransport for TcpTransport {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let _ = self.stream.set_nonblocking(false);
+        let _ = self.stream.write_all(&[out]);
+        let mut buf = [0xFFu8; 1];
+        let _ = self.stream.read_exact(&mut buf);
+        let _ = self.stream.set_nonblocking(true);
+        buf[0]
+    }
+}
+
+impl AsyncTransport for TcpTransport {
+    fn poll(&mut self) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        match self.stream.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+}
+
+/// Prints every byte shifted out over the link port straight to stdout
+/// and never completes an externally-clocked transfer. This is the
+/// default sink, so a ROM that prints its results over serial (e.g.
+/// Blargg's `cpu_instrs` suite) is observable without any setup beyond
+/// running the emulator.
+#[derive(Default)]
+pub struct StdoutTransport;
+
+impl SyncTransport for StdoutTransport {
+    fn exchange(&mut self, out: u8) -> u8 {
+        print!("{}", out as char);
+        let _ = std::io::stdout().flush();
+        0xFF
+    }
+}
+
+impl AsyncTransport for StdoutTransport {
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Captures every byte shifted out over the link port into an in-memory
+/// log instead of a real connection. Test ROMs that self-report over
+/// serial (e.g. Blargg's `cpu_instrs` suite) print their "Passed"/"Failed"
+/// message this way, so plugging this in lets a test read that message
+/// back as a plain `String` instead of needing a real link partner.
+#[derive(Clone, Default)]
+pub struct CaptureTransport {
+    log: Rc<RefCell<String>>,
+}
+
+impl CaptureTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A handle to the captured output so far; keep a clone of this
+    /// before handing the transport to a `Serial`/`Memory`/`Cpu`, since
+    /// that takes ownership of the transport itself.
+    pub fn log(&self) -> Rc<RefCell<String>> {
+        self.log.clone()
+    }
+}
+
+impl SyncTransport for CaptureTransport {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.log.borrow_mut().push(out as char);
+        0xFF
+    }
+}
+
+impl AsyncTransport for CaptureTransport {
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// The Game Boy link port: SB (0xFF01) holds the byte being shifted in
+/// and out, SC (0xFF02) is the control register (bit 7 = transfer
+/// start/in-progress, bit 0 = internal clock).
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    ticks: u32,
+    transferring: bool,
+    inter: u8,
+    transport: Box<dyn Transport>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            ticks: 0,
+            transferring: false,
+            inter: 0,
+            transport: Box::new(StdoutTransport),
+        }
+    }
+}
+
+impl Serial {
+    pub fn set_transport(&mut self, transport: Box<dyn Transport>) {
+        self.transport = transport;
+    }
+
+    pub fn read_sb(&self) -> u8 {
+        self.sb
+    }
+
+    pub fn write_sb(&mut self, value: u8) {
+        self.sb = value;
+    }
+
+    pub fn read_sc(&self) -> u8 {
+        self.sc | 0x7E
+    }
+
+    pub fn write_sc(&mut self, value: u8) {
+        self.sc = value;
+        if (value & 0x80) != 0 && (value & 0x01) != 0 {
+            self.transferring = true;
+            self.ticks = 0;
+        }
+    }
+
+    pub fn interrupt(&self) -> u8 {
+        self.inter
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        self.inter = 0;
+    }
+
+    pub fn cycle(&mut self, ticks: u32) {
+        if self.transferring {
+            self.ticks += ticks;
+            if self.ticks >= TRANSFER_CYCLES {
+                self.ticks -= TRANSFER_CYCLES;
+                self.sb = self.transport.exchange(self.sb);
+                self.sc &= !0x80;
+                self.transferring = false;
+                self.inter = SERIAL_INTERRUPT;
+            }
+        } else if (self.sc & 0x01) == 0 {
+            if let Some(byte) = self.transport.poll() {
+                self.sb = byte;
+                self.sc &= !0x80;
+                self.inter = SERIAL_INTERRUPT;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_internal_clock_transfer_raises_interrupt_after_period() {
+    let mut serial = Serial::default();
+    serial.set_transport(Box::new(NullTransport));
+    serial.write_sb(0x42);
+    serial.write_sc(0x81); // transfer start, internal clock
+
+    serial.cycle(TRANSFER_CYCLES - 1);
+    assert_eq!(serial.interrupt(), 0);
+    assert_eq!(serial.read_sc() & 0x80, 0x80);
+
+    serial.cycle(1);
+    assert_eq!(serial.interrupt(), SERIAL_INTERRUPT);
+    assert_eq!(serial.read_sc() & 0x80, 0);
+}
+
+#[test]
+fn test_clear_interrupt() {
+    let mut serial = Serial::default();
+    serial.set_transport(Box::new(NullTransport));
+    serial.write_sc(0x81);
+    serial.cycle(TRANSFER_CYCLES);
+    assert_eq!(serial.interrupt(), SERIAL_INTERRUPT);
+
+    serial.clear_interrupt();
+    assert_eq!(serial.interrupt(), 0);
+}
@@ -1,3 +1,5 @@
+use crate::savestate::{write_bool, write_u32, Cursor, SaveState};
+
 pub const TIMER_INTERRUPT: u8 = 0x04;
 const DIVIDER_PERIOD: u32 = 256;
 
@@ -10,6 +12,9 @@ pub struct Timer {
     tima: u8,
     tma: u8,
     enabled: bool,
+    /// Previous state of DIV bit 4, the APU frame sequencer's edge
+    /// detector input; see `cycle`.
+    div_bit4: bool,
 }
 
 impl Default for Timer {
@@ -23,6 +28,7 @@ impl Default for Timer {
             tima: 0x00,
             tma: 0x00,
             enabled: false,
+            div_bit4: 0x18 & 0x10 != 0,
         }
     }
 }
@@ -77,12 +83,24 @@ impl Timer {
         self.tma = value
     }
 
-    pub fn cycle(&mut self, ticks: u32) {
+    /// Advance DIV/TIMA by `ticks` and return how many times DIV bit 4
+    /// fell from 1 to 0 along the way, which is the APU frame sequencer's
+    /// ~512 Hz clock; the caller (`Memory::cycle`) steps the APU that
+    /// many times to keep it in lockstep with the timer circuit that
+    /// actually drives it on real hardware.
+    pub fn cycle(&mut self, ticks: u32) -> u32 {
         self.internal_div += ticks;
 
+        let mut apu_steps = 0;
         while self.internal_div >= DIVIDER_PERIOD {
             self.div = self.div.wrapping_add(1u8);
             self.internal_div -= DIVIDER_PERIOD;
+
+            let bit4 = self.div & 0x10 != 0;
+            if self.div_bit4 && !bit4 {
+                apu_steps += 1;
+            }
+            self.div_bit4 = bit4;
         }
 
         if self.enabled {
@@ -97,11 +115,42 @@ impl Timer {
                 self.internal_count -= self.step;
             }
         }
+
+        apu_steps
     }
 
     pub fn interrupt(&self) -> u8 {
         self.inter
     }
+
+    pub fn clear_interrupt(&mut self) {
+        self.inter = 0;
+    }
+}
+
+impl SaveState for Timer {
+    fn write_state(&self, out: &mut Vec<u8>) {
+        write_u32(out, self.internal_div);
+        write_u32(out, self.internal_count);
+        write_u32(out, self.step);
+        out.push(self.div);
+        out.push(self.tima);
+        out.push(self.tma);
+        write_bool(out, self.enabled);
+        write_bool(out, self.div_bit4);
+    }
+
+    fn read_state(&mut self, input: &mut Cursor) -> Result<(), String> {
+        self.internal_div = input.read_u32()?;
+        self.internal_count = input.read_u32()?;
+        self.step = input.read_u32()?;
+        self.div = input.read_u8()?;
+        self.tima = input.read_u8()?;
+        self.tma = input.read_u8()?;
+        self.enabled = input.read_bool()?;
+        self.div_bit4 = input.read_bool()?;
+        Ok(())
+    }
 }
 
 #[test]